@@ -0,0 +1,50 @@
+// Benchmarks adding a block's worth of leaves to a Stump. Run with and without
+// the rayon feature to compare, on a machine with more than one core:
+//
+//   cargo bench --bench stump
+//   cargo bench --bench stump --features rayon
+
+use bitcoin_hashes::{sha256, Hash};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rustreexo::accumulator::stump::{ScratchSpace, Stump};
+
+fn leaves(n: u32) -> Vec<sha256::Hash> {
+    (0..n).map(|i| sha256::Hash::hash(&i.to_le_bytes())).collect()
+}
+
+fn modify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("modify");
+
+    for n in [100, 1_000, 10_000].iter() {
+        let adds = leaves(*n);
+        group.throughput(Throughput::Elements(*n as u64));
+
+        // Into an empty Stump, and into one with 1_000_003 leaves. That's
+        // 0b11110100001001000011, so 9 roots: the new leaves merge with its
+        // smallest trees, while the big ones stay put
+        group.bench_with_input(BenchmarkId::new("empty", n), &adds, |b, adds| {
+            b.iter_batched(Stump::new, |mut s| s.modify(adds, &[]).unwrap(), BatchSize::SmallInput)
+        });
+
+        let mut existing = Stump::new();
+        existing.modify(&leaves(1_000_003), &[]).unwrap();
+        group.bench_with_input(BenchmarkId::new("existing", n), &adds, |b, adds| {
+            b.iter_batched(|| existing.clone(), |mut s| s.modify(adds, &[]).unwrap(), BatchSize::SmallInput)
+        });
+
+        // Same as existing, reusing one ScratchSpace like a node would
+        let mut scratch = ScratchSpace::new();
+        group.bench_with_input(BenchmarkId::new("existing_scratch", n), &adds, |b, adds| {
+            b.iter_batched(
+                || existing.clone(),
+                |mut s| s.modify_with_scratch(adds, &[], &mut scratch).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, modify);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+// Prints the add test vectors as JSON, one object per step. Seeds are taken
+// from the command line, or 0 through 7 if none are given.
+//
+//   cargo run --example vectors -- 0 1 2 3
+
+use bitcoin_hashes::hex::ToHex;
+use rustreexo::accumulator::vectors;
+
+fn main() {
+    let mut seeds: Vec<u8> = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("seeds must be numbers between 0 and 255"))
+        .collect();
+    if seeds.is_empty() {
+        seeds = (0..8).collect();
+    }
+
+    println!("[");
+    let steps = vectors::add_steps(&seeds);
+    for (i, step) in steps.iter().enumerate() {
+        let roots: Vec<String> = step.roots.iter().map(|root| format!("\"{}\"", root[..].to_hex())).collect();
+        let separator = if i + 1 == steps.len() { "" } else { "," };
+
+        println!(
+            "  {{\"seed\": {}, \"leaf\": \"{}\", \"leafs\": {}, \"roots\": [{}], \"serialized\": \"{}\"}}{}",
+            step.seed,
+            step.leaf[..].to_hex(),
+            step.leafs,
+            roots.join(", "),
+            step.serialized.to_hex(),
+            separator
+        );
+    }
+    println!("]");
+}
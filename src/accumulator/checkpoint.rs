@@ -0,0 +1,148 @@
+// Rustreexo
+
+use std::io::{self, Read, Write};
+
+use bitcoin::BlockHash;
+use bitcoin_hashes::Hash;
+
+use super::stump::Stump;
+
+/// CHECKPOINT_VERSION is the version written by `Checkpoint::serialize`. It's
+/// apart from `crate::FORMAT_VERSION`, since checkpoints get hard-coded into
+/// clients and must keep decoding when other formats change. Bumped when the
+/// checkpoint layout changes, with `Checkpoint::deserialize` still reading
+/// every older version.
+pub const CHECKPOINT_VERSION: u8 = 1;
+
+/// Checkpoint is a Stump along with the block it's the state after, so light
+/// clients can ship hard-coded snapshots and resume syncing from them.
+///
+/// Encoded as CHECKPOINT_VERSION (one byte), the block height (u32 LE), the
+/// block hash and then the Stump as written by `Stump::serialize`.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// Height of the block the Stump is the state after
+    pub height: u32,
+    /// Hash of that block
+    pub block_hash: BlockHash,
+    /// The accumulator state
+    pub stump: Stump,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint, returning how many bytes were written
+    pub fn serialize<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        writer.write_all(&[CHECKPOINT_VERSION])?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        writer.write_all(&self.block_hash[..])?;
+        let stump_len = self.stump.serialize(&mut writer)?;
+
+        Ok(1 + 4 + 32 + stump_len)
+    }
+
+    /// Reads a checkpoint written by `serialize`, with this or any older
+    /// CHECKPOINT_VERSION. Errors on versions it doesn't know.
+    ///# Example
+    /// ```
+    ///   use rustreexo::accumulator::checkpoint::Checkpoint;
+    ///   use rustreexo::accumulator::stump::Stump;
+    ///   let checkpoint = Checkpoint { height: 0, block_hash: Default::default(), stump: Stump::new() };
+    ///
+    ///   let mut buf = Vec::new();
+    ///   checkpoint.serialize(&mut buf).unwrap();
+    ///
+    ///   assert_eq!(Checkpoint::deserialize(buf.as_slice()).unwrap().height, 0);
+    /// ```
+    pub fn deserialize<R: Read>(mut reader: R) -> io::Result<Checkpoint> {
+        let mut version = [0u8; 1];
+        reader.read_exact(&mut version)?;
+        match version[0] {
+            1 => Checkpoint::deserialize_v1(reader),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unknown checkpoint version")),
+        }
+    }
+
+    // deserialize_v1 reads what follows the version byte in version 1
+    // checkpoints
+    fn deserialize_v1<R: Read>(mut reader: R) -> io::Result<Checkpoint> {
+        let mut height = [0u8; 4];
+        reader.read_exact(&mut height)?;
+        let mut block_hash = [0u8; 32];
+        reader.read_exact(&mut block_hash)?;
+        let stump = Stump::deserialize(&mut reader)?;
+
+        Ok(Checkpoint {
+            height: u32::from_le_bytes(height),
+            block_hash: BlockHash::from_inner(block_hash),
+            stump,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::BlockHash;
+    use bitcoin_hashes::Hash;
+
+    use super::super::stump::Stump;
+    use super::super::vectors::seed_leaf;
+    use super::Checkpoint;
+
+    #[test]
+    fn test_round_trip() {
+        let mut stump = Stump::new();
+        let leaves: Vec<_> = (0..5).map(seed_leaf).collect();
+        stump.modify(&leaves, &[]).unwrap();
+
+        let checkpoint = Checkpoint {
+            height: 700_000,
+            block_hash: BlockHash::hash(&[7]),
+            stump,
+        };
+
+        let mut buf = Vec::new();
+        let len = checkpoint.serialize(&mut buf).unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(buf[0], super::CHECKPOINT_VERSION);
+        assert_eq!(buf[1..5], 700_000u32.to_le_bytes());
+        assert_eq!(buf[37..], checkpoint.stump.commitment_serialize()[..]);
+
+        let decoded = Checkpoint::deserialize(buf.as_slice()).unwrap();
+        assert_eq!(decoded.height, checkpoint.height);
+        assert_eq!(decoded.block_hash, checkpoint.block_hash);
+        assert_eq!(decoded.stump.roots(), checkpoint.stump.roots());
+
+        // Unknown versions and truncated checkpoints
+        let mut other_version = buf.clone();
+        for version in [0, super::CHECKPOINT_VERSION + 1].iter() {
+            other_version[0] = *version;
+            assert!(Checkpoint::deserialize(other_version.as_slice()).is_err());
+        }
+        assert!(Checkpoint::deserialize(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_v1() {
+        use bitcoin_hashes::hex::FromHex;
+
+        // A version 1 checkpoint at height 3, with a block hash of all 0x11
+        // and two leaves, which must keep decoding whatever the current
+        // version is
+        let v1 = Vec::<u8>::from_hex(
+            "01\
+             03000000\
+             1111111111111111111111111111111111111111111111111111111111111111\
+             0200000000000000\
+             02242b37d8e851f1e86f46790298c7097df06893d6226b7c1453c213e91717de",
+        )
+        .unwrap();
+
+        let checkpoint = Checkpoint::deserialize(v1.as_slice()).unwrap();
+        assert_eq!(checkpoint.height, 3);
+        assert_eq!(checkpoint.block_hash, BlockHash::from_inner([0x11; 32]));
+
+        let mut stump = Stump::new();
+        stump.modify(&[seed_leaf(0), seed_leaf(1)], &[]).unwrap();
+        assert_eq!(checkpoint.stump.roots(), stump.roots());
+    }
+}
@@ -0,0 +1,82 @@
+// Rustreexo
+
+use std::fmt;
+
+/// AccumulatorError is returned when a change to the accumulator state
+/// can't be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccumulatorError {
+    /// Adding `adds` leafs to a forest that already holds `leafs` would grow
+    /// it past the most leafs it can hold.
+    TooManyLeafs { leafs: u64, adds: u64 },
+    /// A forest with `leafs` leafs must have one root per set bit, but `roots`
+    /// were given.
+    RootsMismatch { leafs: u64, roots: usize },
+    /// Asked to roll back `requested` blocks but only `available` were kept.
+    NotEnoughUndoData { requested: usize, available: usize },
+    /// A diff made against a Stump with `expected` leafs was applied to one
+    /// with `leafs` leafs. They're equal when the number of leafs matches but
+    /// the roots don't.
+    DiffMismatch { leafs: u64, expected: u64 },
+    /// A diff doesn't carry the root at `row`, and the Stump it was applied to
+    /// has none to keep.
+    MissingRoot { row: u8 },
+    /// A diff carries a root at `row`, but the forest it leads to has no tree
+    /// at that row.
+    UnexpectedRoot { row: u8 },
+    /// A diff carries more than one root for `row`.
+    DuplicateRoot { row: u8 },
+    /// Rollback data for a forest with `expected` leafs was used on one with
+    /// `leafs` leafs. They're equal when the number of leafs matches but the
+    /// roots don't.
+    RollbackMismatch { leafs: u64, expected: u64 },
+}
+
+impl fmt::Display for AccumulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccumulatorError::TooManyLeafs { leafs, adds } => write!(
+                f,
+                "can't add {} leafs to a forest with {} leafs without going over its size limit",
+                adds, leafs
+            ),
+            AccumulatorError::RootsMismatch { leafs, roots } => write!(
+                f,
+                "a forest with {} leafs has {} roots, got {}",
+                leafs,
+                leafs.count_ones(),
+                roots
+            ),
+            AccumulatorError::NotEnoughUndoData { requested, available } => write!(
+                f,
+                "can't roll back {} blocks, only {} are kept",
+                requested, available
+            ),
+            AccumulatorError::DiffMismatch { leafs, expected } if leafs == expected => {
+                write!(f, "diff is for another forest with {} leafs", leafs)
+            }
+            AccumulatorError::DiffMismatch { leafs, expected } => write!(
+                f,
+                "diff is for a forest with {} leafs, but this one has {}",
+                expected, leafs
+            ),
+            AccumulatorError::MissingRoot { row } => write!(f, "no root at row {}", row),
+            AccumulatorError::UnexpectedRoot { row } => {
+                write!(f, "diff has a root at row {}, where the forest has no tree", row)
+            }
+            AccumulatorError::DuplicateRoot { row } => {
+                write!(f, "diff has more than one root at row {}", row)
+            }
+            AccumulatorError::RollbackMismatch { leafs, expected } if leafs == expected => {
+                write!(f, "rollback data is for another forest with {} leafs", leafs)
+            }
+            AccumulatorError::RollbackMismatch { leafs, expected } => write!(
+                f,
+                "rollback data is for a forest with {} leafs, but this one has {}",
+                expected, leafs
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AccumulatorError {}
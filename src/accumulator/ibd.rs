@@ -0,0 +1,145 @@
+// Rustreexo
+
+use std::collections::VecDeque;
+
+use bitcoin_hashes::sha256;
+
+use super::error::AccumulatorError;
+use super::stump::Stump;
+use super::types;
+
+/// IbdBuilder feeds leaves into a Stump during initial block download. Leaves
+/// can come in chunks of any size, and every `interval` leaves a copy of the
+/// Stump is kept as a checkpoint, to be taken out with `checkpoint`.
+///
+/// Checkpoints are kept until they're taken out, so callers have to drain them
+/// as they go: a small interval with nobody calling `checkpoint` holds a Stump
+/// for every interval added.
+#[derive(Debug, Clone)]
+pub struct IbdBuilder {
+    stump: Stump,
+    interval: u64,
+    checkpoints: VecDeque<Stump>,
+}
+
+impl IbdBuilder {
+    /// Creates a builder that continues from `stump` and takes a checkpoint
+    /// every time the number of leaves is a multiple of `interval`. An
+    /// interval of 0 never takes checkpoints.
+    pub fn new(stump: Stump, interval: u64) -> Self {
+        IbdBuilder {
+            stump,
+            interval,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    /// Adds a chunk of leaves. Errors without adding anything if the chunk
+    /// doesn't fit in the forest.
+    ///# Example
+    /// ```
+    ///   use rustreexo::accumulator::ibd::IbdBuilder;
+    ///   use rustreexo::accumulator::stump::Stump;
+    ///   let mut builder = IbdBuilder::new(Stump::new(), 2);
+    ///
+    ///   builder.add(&[Default::default(); 5]).unwrap();
+    ///
+    ///   assert_eq!(builder.checkpoint().unwrap().leafs(), 2);
+    ///   assert_eq!(builder.checkpoint().unwrap().leafs(), 4);
+    ///   assert!(builder.checkpoint().is_none());
+    /// ```
+    pub fn add(&mut self, leaves: &[sha256::Hash]) -> Result<(), AccumulatorError> {
+        let leafs = self.stump.leafs();
+        let adds = leaves.len() as u64;
+        match leafs.checked_add(adds) {
+            Some(total) if total <= types::MAX_LEAVES => (),
+            _ => return Err(AccumulatorError::TooManyLeafs { leafs, adds }),
+        }
+
+        if self.interval == 0 {
+            return self.stump.modify(leaves, &[]);
+        }
+
+        // Split the chunk at every checkpoint boundary
+        let mut rest = leaves;
+        while !rest.is_empty() {
+            let leafs = self.stump.leafs();
+            let until_checkpoint = self.interval - leafs % self.interval;
+            let (chunk, remaining) = rest.split_at(rest.len().min(until_checkpoint as usize));
+
+            self.stump.modify(chunk, &[])?;
+            if chunk.len() as u64 == until_checkpoint {
+                self.checkpoints.push_back(self.stump.clone());
+            }
+
+            rest = remaining;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the oldest checkpoint not taken out yet
+    pub fn checkpoint(&mut self) -> Option<Stump> {
+        self.checkpoints.pop_front()
+    }
+
+    /// Returns how many checkpoints are waiting to be taken out
+    pub fn pending_checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Returns the Stump with every leaf added so far
+    pub fn stump(&self) -> &Stump {
+        &self.stump
+    }
+
+    /// Consumes the builder, returning the Stump
+    pub fn into_stump(self) -> Stump {
+        self.stump
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::stump::Stump;
+    use super::super::vectors::seed_leaf;
+    use super::IbdBuilder;
+
+    #[test]
+    fn test_checkpoints() {
+        let leaves: Vec<_> = (0..22).map(seed_leaf).collect();
+        let mut builder = IbdBuilder::new(Stump::new(), 4);
+
+        // Uneven chunks, some smaller and some bigger than the interval
+        for chunk in [&leaves[0..3], &leaves[3..4], &leaves[4..13], &leaves[13..22]].iter() {
+            builder.add(chunk).unwrap();
+        }
+        assert_eq!(builder.pending_checkpoints(), 5);
+
+        let mut expected = Stump::new();
+        for leafs in [4, 8, 12, 16, 20].iter() {
+            let checkpoint = builder.checkpoint().unwrap();
+            expected.modify(&leaves[expected.leafs() as usize..*leafs], &[]).unwrap();
+
+            assert_eq!(checkpoint.leafs(), *leafs as u64);
+            assert_eq!(checkpoint.roots(), expected.roots());
+        }
+        assert!(builder.checkpoint().is_none());
+        assert_eq!(builder.pending_checkpoints(), 0);
+
+        expected.modify(&leaves[20..], &[]).unwrap();
+        assert_eq!(builder.stump().roots(), expected.roots());
+        assert_eq!(builder.into_stump().leafs(), 22);
+    }
+
+    #[test]
+    fn test_no_interval() {
+        let leaves: Vec<_> = (0..5).map(seed_leaf).collect();
+        let mut builder = IbdBuilder::new(Stump::new(), 0);
+
+        builder.add(&leaves).unwrap();
+
+        assert!(builder.checkpoint().is_none());
+        assert_eq!(builder.stump().leafs(), 5);
+    }
+}
@@ -0,0 +1,386 @@
+// Rustreexo
+
+use std::io;
+
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKSIG, OP_DUP, OP_EQUAL, OP_EQUALVERIFY, OP_HASH160, OP_PUSHBYTES_0,
+};
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::consensus::encode::{self, Decodable, Encodable};
+use bitcoin::{BlockHash, OutPoint, Script, TxIn, TxOut};
+use bitcoin_hashes::{hash160, sha256, Hash};
+use sha2::{Digest, Sha512_256};
+
+// UTREEXO_TAG_V1 is sha512("UtreexoV1"), the tag leaf hashes start with
+const UTREEXO_TAG_V1: [u8; 64] = [
+    0x5b, 0x83, 0x2d, 0xb8, 0xca, 0x26, 0xc2, 0x5b, 0xe1, 0xc5, 0x42, 0xd6, 0xcc, 0xed, 0xdd, 0xa8,
+    0xc1, 0x45, 0x61, 0x5c, 0xff, 0x5c, 0x35, 0x72, 0x7f, 0xb3, 0x46, 0x26, 0x10, 0x80, 0x7e, 0x20,
+    0xae, 0x53, 0x4d, 0xc3, 0xf6, 0x42, 0x99, 0x19, 0x99, 0x31, 0x77, 0x2e, 0x03, 0x78, 0x7d, 0x18,
+    0x15, 0x6e, 0xb3, 0x15, 0x1e, 0x0e, 0xd1, 0xb3, 0x09, 0x8b, 0xdc, 0x84, 0x45, 0x86, 0x18, 0x85,
+];
+
+/// LeafData is everything that gets committed to when a utxo is added to
+/// the accumulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafData {
+    /// Hash of the block that created the utxo
+    pub block_hash: BlockHash,
+    /// The outpoint being committed to
+    pub prevout: OutPoint,
+    /// Block height shifted left by one, with the LSB set if the utxo was
+    /// created by a coinbase
+    pub header_code: u32,
+    /// The output itself
+    pub utxo: TxOut,
+}
+
+impl LeafData {
+    /// Returns the hash that gets added to the accumulator for this leaf. It's
+    /// sha512_256(tag || tag || block_hash || prevout || header_code || utxo)
+    /// where tag is sha512("UtreexoV1").
+    pub fn leaf_hash(&self) -> sha256::Hash {
+        let hash = Sha512_256::new()
+            .chain_update(UTREEXO_TAG_V1)
+            .chain_update(UTREEXO_TAG_V1)
+            .chain_update(&self.block_hash[..])
+            .chain_update(encode::serialize(&self.prevout))
+            .chain_update(self.header_code.to_le_bytes())
+            .chain_update(encode::serialize(&self.utxo))
+            .finalize();
+
+        sha256::Hash::from_slice(hash.as_slice()).expect("leaf_hash: Engines shouldn't be Err")
+    }
+}
+
+/// ScriptPubkeyType is how a CompactLeafData describes its script. The
+/// common templates are only tagged, as the script can be rebuilt from the
+/// spending input. Everything else is sent in full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptPubkeyType {
+    /// A script not matching any template, sent as is
+    Other(Script),
+    /// P2PKH, rebuilt from the pubkey in the scriptSig
+    PubKeyHash,
+    /// P2WPKH, rebuilt from the pubkey in the witness
+    WitnessV0PubKeyHash,
+    /// P2SH, rebuilt from the redeem script in the scriptSig
+    ScriptHash,
+    /// P2WSH, rebuilt from the witness script in the witness
+    WitnessV0ScriptHash,
+}
+
+impl ScriptPubkeyType {
+    /// Classifies a script into one of the reconstructable templates
+    pub fn from_script(script: &Script) -> ScriptPubkeyType {
+        if script.is_p2pkh() {
+            ScriptPubkeyType::PubKeyHash
+        } else if script.is_v0_p2wpkh() {
+            ScriptPubkeyType::WitnessV0PubKeyHash
+        } else if script.is_p2sh() {
+            ScriptPubkeyType::ScriptHash
+        } else if script.is_v0_p2wsh() {
+            ScriptPubkeyType::WitnessV0ScriptHash
+        } else {
+            ScriptPubkeyType::Other(script.clone())
+        }
+    }
+}
+
+/// CompactLeafData is the LeafData sent over the wire when bandwidth
+/// matters. The block hash and outpoint are known to whoever is validating
+/// the spending transaction, and the script can usually be rebuilt from the
+/// spending input, so only what can't be recovered is kept.
+///
+/// Serialized as header_code (u32 LE), amount (u64 LE), a one byte script
+/// type tag and, for `ScriptPubkeyType::Other`, the full script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactLeafData {
+    /// Block height shifted left by one, with the LSB set for coinbases
+    pub header_code: u32,
+    /// Value of the output in satoshis
+    pub amount: u64,
+    /// The script, or which template to rebuild it from
+    pub spk_ty: ScriptPubkeyType,
+}
+
+impl CompactLeafData {
+    /// Creates a CompactLeafData out of a full LeafData
+    pub fn from_leaf_data(leaf: &LeafData) -> CompactLeafData {
+        CompactLeafData {
+            header_code: leaf.header_code,
+            amount: leaf.utxo.value,
+            spk_ty: ScriptPubkeyType::from_script(&leaf.utxo.script_pubkey),
+        }
+    }
+
+    /// Height of the block that created this utxo
+    pub fn height(&self) -> u32 {
+        self.header_code >> 1
+    }
+
+    /// Whether this utxo was created by a coinbase
+    pub fn is_coinbase(&self) -> bool {
+        self.header_code & 1 == 1
+    }
+
+    /// Rebuilds the scriptPubkey from the input spending it. Returns None if
+    /// the input doesn't carry what the template needs.
+    pub fn reconstruct_script(&self, input: &TxIn) -> Option<Script> {
+        match &self.spk_ty {
+            ScriptPubkeyType::Other(script) => Some(script.clone()),
+            ScriptPubkeyType::PubKeyHash => {
+                let pubkey = last_push(&input.script_sig)?;
+                Some(
+                    Builder::new()
+                        .push_opcode(OP_DUP)
+                        .push_opcode(OP_HASH160)
+                        .push_slice(&hash160::Hash::hash(pubkey)[..])
+                        .push_opcode(OP_EQUALVERIFY)
+                        .push_opcode(OP_CHECKSIG)
+                        .into_script(),
+                )
+            }
+            ScriptPubkeyType::WitnessV0PubKeyHash => {
+                let pubkey = input.witness.last()?;
+                Some(
+                    Builder::new()
+                        .push_opcode(OP_PUSHBYTES_0)
+                        .push_slice(&hash160::Hash::hash(pubkey)[..])
+                        .into_script(),
+                )
+            }
+            ScriptPubkeyType::ScriptHash => {
+                let redeem_script = last_push(&input.script_sig)?;
+                Some(
+                    Builder::new()
+                        .push_opcode(OP_HASH160)
+                        .push_slice(&hash160::Hash::hash(redeem_script)[..])
+                        .push_opcode(OP_EQUAL)
+                        .into_script(),
+                )
+            }
+            ScriptPubkeyType::WitnessV0ScriptHash => {
+                let witness_script = input.witness.last()?;
+                Some(
+                    Builder::new()
+                        .push_opcode(OP_PUSHBYTES_0)
+                        .push_slice(&sha256::Hash::hash(witness_script)[..])
+                        .into_script(),
+                )
+            }
+        }
+    }
+
+    /// Turns this back into the full LeafData, given the block hash and
+    /// outpoint known by the validator and the input spending it.
+    pub fn to_leaf_data(&self, block_hash: BlockHash, prevout: OutPoint, input: &TxIn) -> Option<LeafData> {
+        Some(LeafData {
+            block_hash,
+            prevout,
+            header_code: self.header_code,
+            utxo: TxOut {
+                value: self.amount,
+                script_pubkey: self.reconstruct_script(input)?,
+            },
+        })
+    }
+}
+
+// last_push returns the data of the last push in a script, which is where
+// both the pubkey (P2PKH) and the redeem script (P2SH) live.
+fn last_push(script: &Script) -> Option<&[u8]> {
+    match script.iter(false).last()? {
+        Instruction::PushBytes(data) => Some(data),
+        _ => None,
+    }
+}
+
+impl Encodable for CompactLeafData {
+    fn consensus_encode<W: io::Write>(&self, mut w: W) -> Result<usize, encode::Error> {
+        let mut len = self.header_code.consensus_encode(&mut w)?;
+        len += self.amount.consensus_encode(&mut w)?;
+        len += match &self.spk_ty {
+            ScriptPubkeyType::Other(script) => 0u8.consensus_encode(&mut w)? + script.consensus_encode(&mut w)?,
+            ScriptPubkeyType::PubKeyHash => 1u8.consensus_encode(&mut w)?,
+            ScriptPubkeyType::WitnessV0PubKeyHash => 2u8.consensus_encode(&mut w)?,
+            ScriptPubkeyType::ScriptHash => 3u8.consensus_encode(&mut w)?,
+            ScriptPubkeyType::WitnessV0ScriptHash => 4u8.consensus_encode(&mut w)?,
+        };
+
+        Ok(len)
+    }
+}
+
+impl Decodable for CompactLeafData {
+    fn consensus_decode<R: io::Read>(mut r: R) -> Result<Self, encode::Error> {
+        let header_code = u32::consensus_decode(&mut r)?;
+        let amount = u64::consensus_decode(&mut r)?;
+        let spk_ty = match u8::consensus_decode(&mut r)? {
+            0 => ScriptPubkeyType::Other(Script::consensus_decode(&mut r)?),
+            1 => ScriptPubkeyType::PubKeyHash,
+            2 => ScriptPubkeyType::WitnessV0PubKeyHash,
+            3 => ScriptPubkeyType::ScriptHash,
+            4 => ScriptPubkeyType::WitnessV0ScriptHash,
+            _ => return Err(encode::Error::ParseFailed("unknown script pubkey type")),
+        };
+
+        Ok(CompactLeafData { header_code, amount, spk_ty })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::blockdata::opcodes::all::{OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY, OP_HASH160, OP_RETURN};
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::consensus::encode;
+    use bitcoin::{BlockHash, OutPoint, Script, TxIn, TxOut, Txid};
+    use bitcoin_hashes::hex::ToHex;
+    use bitcoin_hashes::Hash;
+    use sha2::{Digest, Sha512};
+
+    use super::{CompactLeafData, LeafData, ScriptPubkeyType};
+
+    // A coinbase P2PKH output at height 100, with made up hashes
+    fn p2pkh_leaf() -> LeafData {
+        LeafData {
+            block_hash: BlockHash::from_inner([0x11; 32]),
+            prevout: OutPoint::new(Txid::from_inner([0x22; 32]), 3),
+            header_code: (100 << 1) | 1,
+            utxo: TxOut {
+                value: 5_000_000_000,
+                script_pubkey: Builder::new()
+                    .push_opcode(OP_DUP)
+                    .push_opcode(OP_HASH160)
+                    .push_slice(&[0x33; 20])
+                    .push_opcode(OP_EQUALVERIFY)
+                    .push_opcode(OP_CHECKSIG)
+                    .into_script(),
+            },
+        }
+    }
+
+    fn leaf(input: &TxIn, spk_ty: ScriptPubkeyType) -> LeafData {
+        let compact = CompactLeafData {
+            header_code: (100 << 1) | 1,
+            amount: 50_000,
+            spk_ty,
+        };
+
+        compact
+            .to_leaf_data(BlockHash::hash(&[1]), OutPoint::new(Txid::hash(&[2]), 3), input)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reconstruct() {
+        let pubkey = [2u8; 33];
+        let legacy_input = TxIn {
+            script_sig: Builder::new().push_slice(&[0u8; 71]).push_slice(&pubkey).into_script(),
+            ..Default::default()
+        };
+        let witness_input = TxIn {
+            witness: vec![vec![0u8; 71], pubkey.to_vec()],
+            ..Default::default()
+        };
+
+        let cases = [
+            (&legacy_input, ScriptPubkeyType::PubKeyHash),
+            (&legacy_input, ScriptPubkeyType::ScriptHash),
+            (&witness_input, ScriptPubkeyType::WitnessV0PubKeyHash),
+            (&witness_input, ScriptPubkeyType::WitnessV0ScriptHash),
+        ];
+
+        for (input, spk_ty) in cases.iter() {
+            let full = leaf(input, spk_ty.clone());
+
+            // The rebuilt script must be of the template it was tagged as
+            assert_eq!(ScriptPubkeyType::from_script(&full.utxo.script_pubkey), *spk_ty);
+
+            let compact = CompactLeafData::from_leaf_data(&full);
+            assert_eq!(compact.height(), 100);
+            assert!(compact.is_coinbase());
+            assert_eq!(&compact.spk_ty, spk_ty);
+        }
+
+        // Nothing to rebuild from
+        let compact = CompactLeafData {
+            header_code: 0,
+            amount: 0,
+            spk_ty: ScriptPubkeyType::PubKeyHash,
+        };
+        assert_eq!(compact.reconstruct_script(&TxIn::default()), None);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let other = Builder::new().push_opcode(OP_RETURN).push_slice(&[1, 2, 3]).into_script();
+        let leaves = [
+            CompactLeafData { header_code: 5, amount: 1, spk_ty: ScriptPubkeyType::Other(other) },
+            CompactLeafData { header_code: 6, amount: 2, spk_ty: ScriptPubkeyType::WitnessV0ScriptHash },
+        ];
+
+        for leaf in leaves.iter() {
+            let serialized = encode::serialize(leaf);
+            assert_eq!(serialized[0..4], leaf.header_code.to_le_bytes());
+            assert_eq!(serialized[4..12], leaf.amount.to_le_bytes());
+
+            let deserialized: CompactLeafData = encode::deserialize(&serialized).unwrap();
+            assert_eq!(&deserialized, leaf);
+        }
+
+        // Unknown script type
+        let mut serialized = encode::serialize(&leaves[1]);
+        serialized[12] = 5;
+        assert!(encode::deserialize::<CompactLeafData>(&serialized).is_err());
+
+        // Every script type tag, for the output in p2pkh_leaf
+        let prefix = "c900000000f2052a01000000";
+        let tags = [
+            (ScriptPubkeyType::Other(Script::from(vec![0x6a, 0x01, 0x02])), "00036a0102"),
+            (ScriptPubkeyType::PubKeyHash, "01"),
+            (ScriptPubkeyType::WitnessV0PubKeyHash, "02"),
+            (ScriptPubkeyType::ScriptHash, "03"),
+            (ScriptPubkeyType::WitnessV0ScriptHash, "04"),
+        ];
+        for (spk_ty, tag) in tags.iter() {
+            let leaf = CompactLeafData {
+                spk_ty: spk_ty.clone(),
+                ..CompactLeafData::from_leaf_data(&p2pkh_leaf())
+            };
+
+            assert_eq!(encode::serialize(&leaf).to_hex(), format!("{}{}", prefix, tag));
+        }
+    }
+
+    #[test]
+    fn test_leaf_hash() {
+        let full = LeafData {
+            block_hash: BlockHash::hash(&[1]),
+            prevout: OutPoint::new(Txid::hash(&[2]), 3),
+            header_code: 200,
+            utxo: TxOut { value: 10, script_pubkey: Script::new() },
+        };
+
+        let mut other = full.clone();
+        assert_eq!(full.leaf_hash(), other.leaf_hash());
+
+        // Every field is committed to
+        other.header_code += 2;
+        assert_ne!(full.leaf_hash(), other.leaf_hash());
+        other = full.clone();
+        other.prevout.vout = 4;
+        assert_ne!(full.leaf_hash(), other.leaf_hash());
+        other = full.clone();
+        other.utxo.value = 11;
+        assert_ne!(full.leaf_hash(), other.leaf_hash());
+
+        assert_eq!(super::UTREEXO_TAG_V1[..], Sha512::digest(b"UtreexoV1")[..]);
+
+        // Computed apart from this code, by hashing the layout described
+        // on leaf_hash with Python's hashlib
+        assert_eq!(
+            p2pkh_leaf().leaf_hash()[..].to_hex(),
+            "a3ff96c2e110298a617232fac74aabff20d58d474469e454893e40648b9cd3ee"
+        );
+    }
+}
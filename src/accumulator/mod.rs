@@ -1,2 +1,8 @@
+pub mod error;
 pub mod types;
-pub mod stump;
\ No newline at end of file
+pub mod stump;
+pub mod small_stump;
+pub mod leaf_data;
+pub mod vectors;
+pub mod ibd;
+pub mod checkpoint;
\ No newline at end of file
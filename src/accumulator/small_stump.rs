@@ -0,0 +1,143 @@
+// Rustreexo
+
+use bitcoin_hashes::sha256;
+
+use super::error::AccumulatorError;
+use super::types;
+
+/// SmallStump is a Stump for forests that never grow past a few roots. The
+/// roots live inline in a fixed size array, so it never touches the heap,
+/// which makes it usable for embedded verifiers committing to few leaves.
+///
+/// With `MAX_ROOTS` roots it can hold up to `2^MAX_ROOTS - 1` leaves.
+#[derive(Debug, Clone, Copy)]
+pub struct SmallStump<const MAX_ROOTS: usize> {
+    leafs: u64,
+    roots: [sha256::Hash; MAX_ROOTS],
+}
+
+impl<const MAX_ROOTS: usize> Default for SmallStump<MAX_ROOTS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const MAX_ROOTS: usize> SmallStump<MAX_ROOTS> {
+    /// The most leaves a SmallStump with `MAX_ROOTS` roots can hold
+    pub const MAX_LEAVES: u64 = if MAX_ROOTS > types::MAX_ROWS as usize {
+        types::MAX_LEAVES
+    } else {
+        (1 << MAX_ROOTS) - 1
+    };
+
+    /// Creates an empty SmallStump
+    pub fn new() -> Self {
+        SmallStump {
+            leafs: 0,
+            roots: [sha256::Hash::default(); MAX_ROOTS],
+        }
+    }
+
+    /// Adds the given utxos to the accumulator. Errors without touching the
+    /// state if they don't fit in `MAX_ROOTS` roots.
+    ///# Example
+    /// ```
+    ///   use rustreexo::accumulator::small_stump::SmallStump;
+    ///   let mut s = SmallStump::<4>::new();
+    ///   s.modify(&[]).unwrap();
+    ///
+    ///   assert!(s.roots().is_empty());
+    /// ```
+    pub fn modify(&mut self, utxos: &[sha256::Hash]) -> Result<(), AccumulatorError> {
+        let adds = utxos.len() as u64;
+        match self.leafs.checked_add(adds) {
+            Some(leafs) if leafs <= Self::MAX_LEAVES => (),
+            _ => return Err(AccumulatorError::TooManyLeafs { leafs: self.leafs, adds }),
+        }
+
+        for utxo in utxos.iter() {
+            self.add_single(*utxo);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current roots, from the biggest tree to the smallest
+    pub fn roots(&self) -> &[sha256::Hash] {
+        &self.roots[..self.leafs.count_ones() as usize]
+    }
+
+    /// Returns how many leaves were added to the accumulator
+    pub fn leafs(&self) -> u64 {
+        self.leafs
+    }
+
+    fn add_single(&mut self, node: sha256::Hash) {
+        // Same as Stump::add_single, except that the roots aren't popped and
+        // pushed but overwritten in place. There's a root for every bit set in
+        // leafs, so the next free slot is at the popcount.
+        let mut n_roots = self.leafs.count_ones() as usize;
+        let mut to_add = node;
+        let mut h = 0;
+        while (self.leafs >> h) & 1 == 1 {
+            n_roots -= 1;
+            to_add = types::parent_hash(&self.roots[n_roots], &to_add);
+            h += 1;
+        }
+
+        self.roots[n_roots] = to_add;
+
+        self.leafs += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::error::AccumulatorError;
+    use super::super::stump::Stump;
+    use super::SmallStump;
+    use bitcoin_hashes::{sha256, Hash, HashEngine};
+
+    fn hash_from_u8(value: u8) -> sha256::Hash {
+        let mut engine = bitcoin_hashes::sha256::Hash::engine();
+
+        engine.input(&[value]);
+
+        sha256::Hash::from_engine(engine)
+    }
+
+    #[test]
+    fn test_matches_stump() {
+        let mut small = SmallStump::<4>::new();
+        let mut stump = Stump::new();
+
+        for i in 0..15 {
+            let utxo = [hash_from_u8(i)];
+            small.modify(&utxo).unwrap();
+            stump.modify(&utxo, &[]).unwrap();
+
+            let roots: Vec<u8> = small.roots().iter().flat_map(|root| root[..].to_vec()).collect();
+            assert_eq!(small.leafs(), i as u64 + 1);
+            assert_eq!(roots, stump.commitment_serialize()[8..]);
+        }
+    }
+
+    #[test]
+    fn test_full() {
+        let mut s = SmallStump::<2>::new();
+        let utxos: Vec<_> = (0..4).map(hash_from_u8).collect();
+
+        assert_eq!(
+            s.modify(&utxos),
+            Err(AccumulatorError::TooManyLeafs { leafs: 0, adds: 4 })
+        );
+        assert_eq!(s.leafs(), 0);
+
+        s.modify(&utxos[..3]).unwrap();
+        assert_eq!(s.roots().len(), 2);
+        assert!(s.modify(&utxos[3..]).is_err());
+
+        assert_eq!(SmallStump::<0>::MAX_LEAVES, 0);
+        assert_eq!(SmallStump::<64>::MAX_LEAVES, super::types::MAX_LEAVES);
+    }
+}
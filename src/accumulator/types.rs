@@ -1,12 +1,28 @@
 // Rustreexo
-use bitcoin::hashes::{sha256, Hash};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+use bitcoin::consensus::encode::{self, Decodable, Encodable, VarInt};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 
 extern crate sha2;
 
 use sha2::{Digest, Sha512_256};
 
+/// MAX_ROWS is the number of rows the biggest tree in the forest can have.
+/// Positions are u64s and a forest with n rows needs (2 << n) - 1 of them,
+/// so 63 is the most a forest can grow to.
+pub const MAX_ROWS: u8 = 63;
+
+/// MAX_LEAVES is the number of leaves a forest with MAX_ROWS rows can hold.
+pub const MAX_LEAVES: u64 = 1 << MAX_ROWS;
+
 // parent_hash return the merkle parent of the two passed in nodes
 pub fn parent_hash(left: &sha256::Hash, right: &sha256::Hash) -> sha256::Hash {
+    #[cfg(test)]
+    PARENT_HASHES.with(|count| count.set(count.get() + 1));
+
     let hash = Sha512_256::new()
         .chain_update(left)
         .chain_update(right)
@@ -15,6 +31,351 @@ pub fn parent_hash(left: &sha256::Hash, right: &sha256::Hash) -> sha256::Hash {
     sha256::Hash::from_slice(hash.as_slice()).expect("parent_hash: Engines shouldn't be Err")
 }
 
+// parent_hash_batch returns the merkle parents of each (left, right) pair, in
+// the same order they were passed in. Callers hashing many independent pairs
+// should go through here or parent_hash_batch_into
+pub fn parent_hash_batch(pairs: &[(sha256::Hash, sha256::Hash)]) -> Vec<sha256::Hash> {
+    let mut parents = vec![sha256::Hash::default(); pairs.len()];
+    parent_hash_batch_into(pairs, &mut parents);
+
+    parents
+}
+
+// parent_hash_batch_into writes the merkle parent of each (left, right) pair
+// to the same index of `parents`, for callers reusing their buffers. Every
+// batch of hashes goes through here, so the hashing backend can be swapped in
+// one place
+pub fn parent_hash_batch_into(pairs: &[(sha256::Hash, sha256::Hash)], parents: &mut [sha256::Hash]) {
+    assert_eq!(pairs.len(), parents.len(), "parent_hash_batch_into: one parent per pair");
+
+    for (parent, (left, right)) in parents.iter_mut().zip(pairs.iter()) {
+        *parent = parent_hash(left, right);
+    }
+}
+
+// digest hashes a run of roots, in order. Roots::digest is this over all of
+// them, RollbackBuffer uses it on the roots a block added.
+pub(crate) fn digest(roots: &[sha256::Hash]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    for root in roots.iter() {
+        engine.input(&root[..]);
+    }
+
+    sha256::Hash::from_engine(engine)
+}
+
+/// empty_roots returns, for every row, the root of a subtree at that row where
+/// every leaf is the all-zeros placeholder hash. Row 0 is the placeholder
+/// itself, and every other row is the parent_hash of two of the row below.
+/// They're computed on the first call.
+pub fn empty_roots() -> &'static [sha256::Hash; MAX_ROWS as usize + 1] {
+    static EMPTY_ROOTS: OnceLock<[sha256::Hash; MAX_ROWS as usize + 1]> = OnceLock::new();
+
+    EMPTY_ROOTS.get_or_init(|| {
+        let mut roots = [sha256::Hash::default(); MAX_ROWS as usize + 1];
+        for row in 1..roots.len() {
+            roots[row] = parent_hash(&roots[row - 1], &roots[row - 1]);
+        }
+
+        roots
+    })
+}
+
+// Number of parent_hash calls made by the current thread. Tests use it through
+// assert_hash_count! so that algorithmic regressions show up as failures and
+// not only in benchmarks.
+#[cfg(test)]
+thread_local! {
+    static PARENT_HASHES: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+pub(crate) fn parent_hash_count() -> u64 {
+    PARENT_HASHES.with(|count| count.get())
+}
+
+// assert_hash_count! evaluates an expression, asserts it called parent_hash
+// exactly the expected amount of times and returns its value.
+#[cfg(test)]
+macro_rules! assert_hash_count {
+    ($op:expr, $expected:expr) => {{
+        let before = $crate::accumulator::types::parent_hash_count();
+        let ret = $op;
+        assert_eq!(
+            $crate::accumulator::types::parent_hash_count() - before,
+            $expected,
+            "unexpected parent_hash count for `{}`",
+            stringify!($op)
+        );
+        ret
+    }};
+}
+
+#[cfg(test)]
+pub(crate) use assert_hash_count;
+
+/// Position is the absolute position of a node in the forest, counting from
+/// the leftmost leaf and going up row by row. Most operations need to know how
+/// many rows the forest has.
+///
+/// ```text
+/// 06
+/// |-------\
+/// 04      05
+/// |---\   |---\
+/// 00  01  02  03
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Position(pub u64);
+
+impl Position {
+    /// Returns the position of the node at `offset` in `row`, or None if
+    /// there's no such node in a forest with `forest_rows` rows
+    pub fn from_row_offset(row: u8, offset: u64, forest_rows: u8) -> Option<Position> {
+        if forest_rows > MAX_ROWS || row > forest_rows || offset >> (forest_rows - row) != 0 {
+            return None;
+        }
+
+        Some(Position(row_offset(row, forest_rows) + offset))
+    }
+
+    /// Returns the row this position is at, 0 being the leaves, or None if
+    /// it's not in a forest with `forest_rows` rows
+    pub fn row(self, forest_rows: u8) -> Option<u8> {
+        if forest_rows > MAX_ROWS {
+            return None;
+        }
+
+        let mut marker: u64 = 1 << forest_rows;
+        let mut row = 0;
+
+        while self.0 & marker != 0 {
+            marker >>= 1;
+            row += 1;
+        }
+
+        if row > forest_rows {
+            return None;
+        }
+
+        Some(row)
+    }
+
+    /// Returns how far from the leftmost node of its row this position is, or
+    /// None if it's not in a forest with `forest_rows` rows
+    pub fn offset(self, forest_rows: u8) -> Option<u64> {
+        let row = self.row(forest_rows)?;
+
+        Some(self.0 - row_offset(row, forest_rows))
+    }
+
+    /// Returns the position of this node's parent, or None if it's a root
+    /// spanning the whole forest or not in a forest with `forest_rows` rows
+    pub fn parent(self, forest_rows: u8) -> Option<Position> {
+        if self.row(forest_rows)? == forest_rows {
+            return None;
+        }
+
+        Some(Position((self.0 >> 1) | (1 << forest_rows)))
+    }
+
+    /// Returns the position of the other child of this node's parent
+    pub fn sibling(self) -> Position {
+        Position(self.0 ^ 1)
+    }
+}
+
+impl From<u64> for Position {
+    fn from(pos: u64) -> Self {
+        Position(pos)
+    }
+}
+
+impl From<Position> for u64 {
+    fn from(pos: Position) -> Self {
+        pos.0
+    }
+}
+
+// row_offset returns the first position of a row. 2 << forest_rows is 2 more
+// than the max position, so the row complement is subtracted from it. Wrapping
+// math keeps this right for a forest with MAX_ROWS rows, where 2 << forest_rows
+// doesn't fit in a u64. Callers make sure row <= forest_rows <= MAX_ROWS.
+fn row_offset(row: u8, forest_rows: u8) -> u64 {
+    (2u64 << forest_rows).wrapping_sub(2 << (forest_rows - row))
+}
+
+/// ForestStats is a snapshot of an accumulator's shape, with the same meaning
+/// whatever kind of accumulator it was taken from, so nodes running different
+/// implementations can be compared.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ForestStats {
+    /// Number of leaves ever added
+    pub leaves: u64,
+    /// Number of roots, one per tree
+    pub num_roots: u64,
+    /// Rows in the forest, enough to fit every tree
+    pub rows: u8,
+    /// Nodes held in memory besides the roots
+    pub cached_nodes: u64,
+    /// Leaves held in memory
+    pub cached_leaves: u64,
+}
+
+impl ForestStats {
+    /// Stats of a forest with `leaves` leaves and nothing cached
+    pub fn from_leaves(leaves: u64) -> ForestStats {
+        ForestStats {
+            leaves,
+            num_roots: leaves.count_ones() as u64,
+            rows: tree_rows(leaves),
+            cached_nodes: 0,
+            cached_leaves: 0,
+        }
+    }
+}
+
+// tree_rows returns the number of rows needed to fit n leaves, log2 of the
+// next power of two
+fn tree_rows(n: u64) -> u8 {
+    if n <= 1 {
+        return 0;
+    }
+
+    (64 - (n - 1).leading_zeros()) as u8
+}
+
+/// Roots are the roots of a forest, ordered from the biggest tree to the
+/// smallest. There's one root for every bit set in the number of leaves, so
+/// two forests with the same leaves are the same if their Roots are equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct Roots(Vec<sha256::Hash>);
+
+// Deserializing checks there aren't more roots than a forest can have, like
+// the other ways of reading Roots do
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Roots {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let roots = Vec::<sha256::Hash>::deserialize(deserializer)?;
+        if roots.len() > MAX_ROWS as usize + 1 {
+            return Err(serde::de::Error::invalid_length(roots.len(), &"at most 64 roots"));
+        }
+
+        Ok(Roots(roots))
+    }
+}
+
+impl Roots {
+    /// Creates a new set of roots, which must be ordered from the biggest tree
+    /// to the smallest
+    pub fn new(roots: Vec<sha256::Hash>) -> Self {
+        Roots(roots)
+    }
+
+    /// Returns a single hash committing to all the roots, in order
+    pub fn digest(&self) -> sha256::Hash {
+        digest(&self.0)
+    }
+
+    /// Pairs each root with the row it's at, given the number of leaves in the
+    /// forest these roots belong to
+    pub fn with_rows(&self, num_leaves: u64) -> impl Iterator<Item = (u8, &sha256::Hash)> {
+        let rows = (0..=MAX_ROWS).rev().filter(move |row| (num_leaves >> row) & 1 == 1);
+        rows.zip(self.0.iter())
+    }
+
+    /// Writes `crate::FORMAT_VERSION` as one byte, then the roots the same way
+    /// as their consensus encoding, a VarInt count followed by the roots.
+    /// Returns how many bytes were written.
+    pub fn serialize<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        writer.write_all(&[crate::FORMAT_VERSION])?;
+        let len = self.consensus_encode(writer).map_err(encode_to_io_error)?;
+
+        Ok(1 + len)
+    }
+
+    /// Reads roots written by `serialize`. Errors on roots written with other
+    /// format versions.
+    pub fn deserialize<R: Read>(mut reader: R) -> io::Result<Roots> {
+        let mut version = [0u8; 1];
+        reader.read_exact(&mut version)?;
+        if version[0] != crate::FORMAT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown roots format version"));
+        }
+
+        Roots::consensus_decode(reader).map_err(encode_to_io_error)
+    }
+
+    pub(crate) fn push(&mut self, root: sha256::Hash) {
+        self.0.push(root);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<sha256::Hash> {
+        self.0.pop()
+    }
+}
+
+impl Deref for Roots {
+    type Target = [sha256::Hash];
+
+    fn deref(&self) -> &[sha256::Hash] {
+        &self.0
+    }
+}
+
+// Roots are encoded the way rust-bitcoin encodes lists, a VarInt count
+// followed by the items, so they can be put straight into network messages.
+impl Encodable for Roots {
+    fn consensus_encode<W: Write>(&self, mut w: W) -> Result<usize, encode::Error> {
+        let mut len = VarInt(self.0.len() as u64).consensus_encode(&mut w)?;
+        for root in self.0.iter() {
+            len += root.into_inner().consensus_encode(&mut w)?;
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for Roots {
+    fn consensus_decode<R: Read>(mut r: R) -> Result<Self, encode::Error> {
+        let len = VarInt::consensus_decode(&mut r)?.0;
+        if len > MAX_ROWS as u64 + 1 {
+            return Err(encode::Error::ParseFailed("too many roots"));
+        }
+
+        let mut roots = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            roots.push(sha256::Hash::from_inner(<[u8; 32]>::consensus_decode(&mut r)?));
+        }
+
+        Ok(Roots(roots))
+    }
+}
+
+// encode_to_io_error turns the errors consensus encoding gives back into the
+// io errors the rest of the crate's serialization uses
+fn encode_to_io_error(err: encode::Error) -> io::Error {
+    match err {
+        encode::Error::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+impl From<Vec<sha256::Hash>> for Roots {
+    fn from(roots: Vec<sha256::Hash>) -> Self {
+        Roots(roots)
+    }
+}
+
+impl From<Roots> for Vec<sha256::Hash> {
+    fn from(roots: Roots) -> Self {
+        roots.0
+    }
+}
+
 #[cfg(test)]
 mod test {
     use bitcoin_hashes::{sha256, Hash, HashEngine};
@@ -35,4 +396,173 @@ mod test {
         
         assert_eq!(parent_hash[0..2], [0x02, 0x24]);
     }
+
+    #[test]
+    fn test_parent_hash_batch() {
+        let pairs: Vec<_> = (0..8)
+            .map(|i| (hash_from_u8(i * 2), hash_from_u8(i * 2 + 1)))
+            .collect();
+
+        let parents = super::assert_hash_count!(super::parent_hash_batch(&pairs), 8);
+
+        assert_eq!(parents.len(), pairs.len());
+        for (parent, (left, right)) in parents.iter().zip(pairs.iter()) {
+            assert_eq!(*parent, super::parent_hash(left, right));
+        }
+        assert_eq!(parents[0][0..2], [0x02, 0x24]);
+        assert!(super::parent_hash_batch(&[]).is_empty());
+
+        let mut into = vec![sha256::Hash::default(); pairs.len()];
+        super::parent_hash_batch_into(&pairs, &mut into);
+        assert_eq!(into, parents);
+    }
+
+    #[test]
+    fn test_empty_roots() {
+        let empty = super::empty_roots();
+
+        assert_eq!(empty.len(), super::MAX_ROWS as usize + 1);
+        assert_eq!(empty[0], sha256::Hash::default());
+
+        // Build a 4 leaf tree out of placeholders by hand
+        let leaf = sha256::Hash::default();
+        let row1 = super::parent_hash(&leaf, &leaf);
+        assert_eq!(empty[1], row1);
+        assert_eq!(empty[2], super::parent_hash(&row1, &row1));
+
+        for row in 1..empty.len() {
+            assert_eq!(empty[row], super::parent_hash(&empty[row - 1], &empty[row - 1]));
+        }
+
+        // Only computed once
+        assert!(std::ptr::eq(empty, super::empty_roots()));
+    }
+
+    #[test]
+    fn test_position() {
+        use super::Position;
+
+        // Positions for a forest with 3 rows
+        // 14
+        // |---------------\
+        // 12              13
+        // |-------\       |-------\
+        // 08      09      10      11
+        // |---\   |---\   |---\   |---\
+        // 00  01  02  03  04  05  06  07
+        let rows = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3];
+        let offsets = [0, 1, 2, 3, 4, 5, 6, 7, 0, 1, 2, 3, 0, 1, 0];
+        let parents = [8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13, 14, 14];
+
+        for pos in 0..15u64 {
+            let p = Position::from(pos);
+            let row = p.row(3).unwrap();
+            assert_eq!(row, rows[pos as usize]);
+            assert_eq!(p.offset(3), Some(offsets[pos as usize]));
+            assert_eq!(Position::from_row_offset(row, p.offset(3).unwrap(), 3), Some(p));
+            assert_eq!(p.parent(3).map(u64::from), parents.get(pos as usize).copied());
+        }
+        assert_eq!(Position(12).sibling(), Position(13));
+        assert_eq!(Position(13).sibling(), Position(12));
+
+        // The top of the biggest forest possible
+        let top = Position::from_row_offset(super::MAX_ROWS, 0, super::MAX_ROWS).unwrap();
+        assert_eq!(top, Position(u64::MAX - 1));
+        assert_eq!(top.row(super::MAX_ROWS), Some(super::MAX_ROWS));
+        assert_eq!(Position(u64::MAX - 2).parent(super::MAX_ROWS), Some(top));
+
+        // Positions and rows outside of the forest
+        assert_eq!(Position::from(15).row(3), None);
+        assert_eq!(Position::from(15).offset(3), None);
+        assert_eq!(Position::from(15).parent(3), None);
+        assert_eq!(Position(u64::MAX).row(super::MAX_ROWS), None);
+        assert_eq!(Position::from_row_offset(4, 0, 3), None);
+        assert_eq!(Position::from_row_offset(1, 4, 3), None);
+        assert_eq!(Position::from_row_offset(0, 0, 64), None);
+        assert_eq!(Position::from(0).row(64), None);
+        assert_eq!(Position::from(0).parent(64), None);
+    }
+
+    #[test]
+    fn test_forest_stats() {
+        let rows = [(0, 0), (1, 0), (2, 1), (3, 2), (4, 2), (5, 3), (8, 3), (9, 4)];
+        for (leaves, expected) in rows.iter() {
+            assert_eq!(super::tree_rows(*leaves), *expected);
+        }
+        assert_eq!(super::tree_rows(super::MAX_LEAVES), super::MAX_ROWS);
+
+        let stats = super::ForestStats::from_leaves(11);
+        assert_eq!(stats.leaves, 11);
+        assert_eq!(stats.num_roots, 3);
+        assert_eq!(stats.rows, 4);
+        assert_eq!(stats.cached_nodes, 0);
+        assert_eq!(stats.cached_leaves, 0);
+    }
+
+    #[test]
+    fn test_roots() {
+        let roots = super::Roots::new((0..3).map(hash_from_u8).collect());
+
+        // 11 leaves are a tree with 3 rows, one with 1 and one with 0
+        let rows: Vec<_> = roots.with_rows(11).map(|(row, _)| row).collect();
+        assert_eq!(rows, vec![3, 1, 0]);
+        assert_eq!(roots.with_rows(11).nth(1).unwrap().1, &roots[1]);
+
+        let mut serialized = Vec::new();
+        assert_eq!(roots.serialize(&mut serialized).unwrap(), serialized.len());
+        assert_eq!(serialized[0], crate::FORMAT_VERSION);
+        assert_eq!(serialized[1..], bitcoin::consensus::encode::serialize(&roots)[..]);
+        let deserialized = super::Roots::deserialize(serialized.as_slice()).unwrap();
+        assert_eq!(deserialized, roots);
+        assert_eq!(deserialized.digest(), roots.digest());
+
+        // Garbage lengths, truncated roots and other versions must error out
+        let mut garbage = vec![crate::FORMAT_VERSION];
+        garbage.extend_from_slice(&[0xff; 9]);
+        garbage.extend_from_slice(&serialized[2..]);
+        let err = super::Roots::deserialize(garbage.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let err = super::Roots::deserialize(&serialized[..40]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        let mut other_version = serialized.clone();
+        other_version[0] = crate::FORMAT_VERSION + 1;
+        assert!(super::Roots::deserialize(other_version.as_slice()).is_err());
+
+        assert_ne!(roots.digest(), super::Roots::new(roots[..2].to_vec()).digest());
+    }
+
+    #[test]
+    fn test_roots_consensus_encoding() {
+        use bitcoin::consensus::encode;
+
+        let roots = super::Roots::new((0..3).map(hash_from_u8).collect());
+
+        let serialized = encode::serialize(&roots);
+        assert_eq!(serialized.len(), 1 + 3 * 32);
+        assert_eq!(serialized[0], 3);
+        assert_eq!(serialized[1..33], roots[0][..]);
+        assert_eq!(encode::deserialize::<super::Roots>(&serialized).unwrap(), roots);
+
+        let empty = encode::serialize(&super::Roots::default());
+        assert_eq!(empty, vec![0]);
+
+        // More roots than a forest can have
+        assert!(encode::deserialize::<super::Roots>(&[65]).is_err());
+        assert!(encode::deserialize::<super::Roots>(&serialized[..40]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_roots_serde() {
+        let roots = super::Roots::new((0..3).map(hash_from_u8).collect());
+        let json = serde_json::to_string(&roots).unwrap();
+        assert_eq!(serde_json::from_str::<super::Roots>(&json).unwrap(), roots);
+
+        // More roots than a forest can have
+        let too_many = super::Roots::new(vec![hash_from_u8(0); super::MAX_ROWS as usize + 2]);
+        let json = serde_json::to_string(&too_many).unwrap();
+        assert!(serde_json::from_str::<super::Roots>(&json).is_err());
+        let bytes = bincode::serialize(&too_many).unwrap();
+        assert!(bincode::deserialize::<super::Roots>(&bytes).is_err());
+    }
 }
\ No newline at end of file
@@ -1 +1,13 @@
 pub mod accumulator;
+pub mod prelude;
+
+/// Version of this library, as in Cargo.toml
+pub const LIB_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Version of the crate's own serialization formats, written first by
+/// `Roots::serialize` and `StumpDiff::serialize`. Bumped whenever one of them
+/// changes incompatibly. Formats meant to be read by other implementations,
+/// like `Stump::serialize` and consensus encoding, carry no version.
+/// Checkpoints have their own, `checkpoint::CHECKPOINT_VERSION`.
+pub const FORMAT_VERSION: u8 = 1;
+
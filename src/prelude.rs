@@ -0,0 +1,18 @@
+//! The types most users of the crate need, in one place.
+//!
+//! ```
+//! use rustreexo::prelude::*;
+//!
+//! let mut s = Stump::new();
+//! s.modify(&[NodeHash::default()], &[]).unwrap();
+//! ```
+
+/// The hash of every leaf and node in the accumulator
+pub use bitcoin_hashes::sha256::Hash as NodeHash;
+
+pub use crate::accumulator::checkpoint::Checkpoint;
+pub use crate::accumulator::error::AccumulatorError;
+pub use crate::accumulator::ibd::IbdBuilder;
+pub use crate::accumulator::small_stump::SmallStump;
+pub use crate::accumulator::stump::{RollbackBuffer, ScratchSpace, Stump, StumpDiff};
+pub use crate::accumulator::types::{ForestStats, Position, Roots};